@@ -0,0 +1,127 @@
+// A simple 2D bounding-volume hierarchy over scene entities: recursively
+// splits entities by the midpoint of the longest axis of their centroid
+// bounds, so `Scene::intersect` can skip subtrees whose box the ray misses,
+// or hits no closer than the current best candidate, instead of testing
+// every entity in the scene.
+
+use {distance, Aabb, Entity, EntityIntersection};
+
+enum Node {
+    Leaf { index: usize, bounds: Aabb },
+    Internal { bounds: Aabb, left: Box<Node>, right: Box<Node> },
+}
+
+impl Node {
+    fn bounds(&self) -> Aabb {
+        match *self {
+            Node::Leaf { bounds, .. } => bounds,
+            Node::Internal { bounds, .. } => bounds,
+        }
+    }
+}
+
+pub struct Bvh {
+    root: Option<Node>,
+}
+
+impl Bvh {
+    /// Builds a tree over every entity with a finite `Aabb`. Entities with
+    /// an unbounded shape are left out; the caller must check those with a
+    /// linear scan.
+    pub fn build(entities: &[Entity]) -> Bvh {
+        let items: Vec<(usize, Aabb)> = entities.iter()
+            .enumerate()
+            .map(|(i, e)| (i, e.shape.bounds()))
+            .filter(|&(_, b)| b.is_finite())
+            .collect();
+        Bvh { root: build_node(items) }
+    }
+
+    pub fn nearest(&self,
+                    entities: &[Entity],
+                    p: (f64, f64),
+                    d: (f64, f64))
+                    -> Option<EntityIntersection> {
+        let mut best = None;
+        if let Some(ref root) = self.root {
+            visit(root, entities, p, d, &mut best);
+        }
+        best
+    }
+}
+
+fn visit(node: &Node,
+         entities: &[Entity],
+         p: (f64, f64),
+         d: (f64, f64),
+         best: &mut Option<EntityIntersection>) {
+    let best_t = best.as_ref().map(|b| distance(p, b.point)).unwrap_or(std::f64::INFINITY);
+    match node.bounds().hit(p, d) {
+        Some(t) if t < best_t => {}
+        _ => return,
+    }
+    match *node {
+        Node::Leaf { index, .. } => {
+            if let Some(hit) = entities[index].intersect(p, d) {
+                if distance(p, hit.point) < best_t {
+                    *best = Some(hit);
+                }
+            }
+        }
+        Node::Internal { ref left, ref right, .. } => {
+            visit(left, entities, p, d, best);
+            visit(right, entities, p, d, best);
+        }
+    }
+}
+
+fn build_node(mut items: Vec<(usize, Aabb)>) -> Option<Node> {
+    if items.is_empty() {
+        return None;
+    }
+    if items.len() == 1 {
+        let (index, bounds) = items[0];
+        return Some(Node::Leaf { index: index, bounds: bounds });
+    }
+    let bounds = items[1..].iter().fold(items[0].1, |acc, &(_, b)| acc.union(&b));
+    let (min_cx, max_cx) = min_max(items.iter().map(|&(_, b)| b.centroid().0));
+    let (min_cy, max_cy) = min_max(items.iter().map(|&(_, b)| b.centroid().1));
+    let axis_x = (max_cx - min_cx) >= (max_cy - min_cy);
+    let mid = if axis_x { (min_cx + max_cx) * 0.5 } else { (min_cy + max_cy) * 0.5 };
+
+    let (mut left, mut right): (Vec<_>, Vec<_>) = items.iter()
+        .cloned()
+        .partition(|&(_, b)| {
+            let c = b.centroid();
+            (if axis_x { c.0 } else { c.1 }) < mid
+        });
+    if left.is_empty() || right.is_empty() {
+        // All centroids landed on one side of the midpoint; fall back to an
+        // even split by sorted order so the recursion still makes progress.
+        items.sort_by(|a, b| {
+            let ca = if axis_x { a.1.centroid().0 } else { a.1.centroid().1 };
+            let cb = if axis_x { b.1.centroid().0 } else { b.1.centroid().1 };
+            ca.partial_cmp(&cb).unwrap()
+        });
+        right = items.split_off(items.len() / 2);
+        left = items;
+    }
+
+    match (build_node(left), build_node(right)) {
+        (Some(l), Some(r)) => {
+            Some(Node::Internal {
+                bounds: bounds,
+                left: Box::new(l),
+                right: Box::new(r),
+            })
+        }
+        (Some(l), None) => Some(l),
+        (None, Some(r)) => Some(r),
+        (None, None) => None,
+    }
+}
+
+fn min_max<I: Iterator<Item = f64>>(mut iter: I) -> (f64, f64) {
+    let first = iter.next().unwrap();
+    iter.fold((first, first), |(lo, hi), v| (lo.min(v), hi.max(v)))
+}
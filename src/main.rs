@@ -1,6 +1,10 @@
 extern crate image;
 extern crate rand;
 extern crate rayon;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 
 use std::f64::consts::PI;
 use std::cmp::min;
@@ -8,13 +12,19 @@ use image::{ImageBuffer, Rgb};
 use rand::{Rng, ThreadRng};
 use rayon::prelude::*;
 
-const W: u32 = 512;
-const H: u32 = 512;
-const N: u32 = 256;
+mod bvh;
+mod scene;
+mod sdf;
+mod transform;
+
 const EPSILON: f64 = 1e-6;
-const MAX_DEPTH: u32 = 3;
+// Absolute recursion cap for `trace`: Russian roulette terminates paths
+// only probabilistically, and a surface can spawn several branches per
+// bounce, so this bounds the depth of every path regardless of the rolls
+// (and of `max_depth`).
+const HARD_DEPTH_CAP: u32 = 64;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Deserialize)]
 struct Color {
     r: f64,
     g: f64,
@@ -29,6 +39,24 @@ impl Color {
             b: 0.0,
         }
     }
+
+    fn white() -> Self {
+        Self {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+        }
+    }
+
+    fn max_channel(&self) -> f64 {
+        self.r.max(self.g).max(self.b)
+    }
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Color::black()
+    }
 }
 
 impl std::ops::Add<Color> for Color {
@@ -81,9 +109,69 @@ struct Intersection {
     normal: (f64, f64),
 }
 
+/// An axis-aligned bounding box, used to accelerate `Scene::intersect` with
+/// a BVH. `Aabb::infinite()` marks a shape as unbounded (e.g. a `Plane`),
+/// which excludes it from the tree; such shapes are checked with a linear
+/// scan instead.
+#[derive(Clone, Copy, Debug)]
+struct Aabb {
+    min: (f64, f64),
+    max: (f64, f64),
+}
+
+impl Aabb {
+    fn infinite() -> Aabb {
+        Aabb {
+            min: (std::f64::NEG_INFINITY, std::f64::NEG_INFINITY),
+            max: (std::f64::INFINITY, std::f64::INFINITY),
+        }
+    }
+
+    fn is_finite(&self) -> bool {
+        self.min.0.is_finite() && self.min.1.is_finite() && self.max.0.is_finite() &&
+        self.max.1.is_finite()
+    }
+
+    fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+            max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
+        }
+    }
+
+    fn centroid(&self) -> (f64, f64) {
+        ((self.min.0 + self.max.0) * 0.5, (self.min.1 + self.max.1) * 0.5)
+    }
+
+    /// Slab test; returns the ray's entry `t` if it hits the box at all.
+    fn hit(&self, p: (f64, f64), d: (f64, f64)) -> Option<f64> {
+        let mut tmin = std::f64::NEG_INFINITY;
+        let mut tmax = std::f64::INFINITY;
+        for &(p_axis, d_axis, lo, hi) in
+            &[(p.0, d.0, self.min.0, self.max.0), (p.1, d.1, self.min.1, self.max.1)] {
+            if d_axis.abs() < EPSILON {
+                if p_axis < lo || p_axis > hi {
+                    return None;
+                }
+            } else {
+                let t1 = (lo - p_axis) / d_axis;
+                let t2 = (hi - p_axis) / d_axis;
+                let (t1, t2) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+                tmin = tmin.max(t1);
+                tmax = tmax.min(t2);
+                if tmin > tmax {
+                    return None;
+                }
+            }
+        }
+        Some(tmin)
+    }
+}
+
 trait Shape {
     fn intersect(&self, p: (f64, f64), d: (f64, f64)) -> Option<Intersection>;
     fn is_inside(&self, p: (f64, f64)) -> bool;
+    fn bounds(&self) -> Aabb;
 }
 
 struct Circle {
@@ -131,6 +219,13 @@ impl Shape for Circle {
         let y = p.1 - self.cy;
         x * x + y * y < self.r * self.r
     }
+
+    fn bounds(&self) -> Aabb {
+        Aabb {
+            min: (self.cx - self.r, self.cy - self.r),
+            max: (self.cx + self.r, self.cy + self.r),
+        }
+    }
 }
 
 struct Plane {
@@ -162,6 +257,10 @@ impl Shape for Plane {
     fn is_inside(&self, p: (f64, f64)) -> bool {
         (p.0 - self.px) * self.nx + (p.1 - self.py) * self.ny < 0.0
     }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::infinite()
+    }
 }
 
 struct Polygon {
@@ -253,6 +352,16 @@ impl Shape for Polygon {
         }
         true
     }
+
+    fn bounds(&self) -> Aabb {
+        let first = self.points[0];
+        self.points[1..].iter().fold(Aabb { min: first, max: first }, |acc, &(x, y)| {
+            Aabb {
+                min: (acc.min.0.min(x), acc.min.1.min(y)),
+                max: (acc.max.0.max(x), acc.max.1.max(y)),
+            }
+        })
+    }
 }
 
 struct UnionShape {
@@ -280,6 +389,10 @@ impl Shape for UnionShape {
     fn is_inside(&self, p: (f64, f64)) -> bool {
         self.a.is_inside(p) || self.b.is_inside(p)
     }
+
+    fn bounds(&self) -> Aabb {
+        self.a.bounds().union(&self.b.bounds())
+    }
 }
 
 impl UnionShape {
@@ -337,6 +450,10 @@ impl Shape for IntersectShape {
     fn is_inside(&self, p: (f64, f64)) -> bool {
         self.a.is_inside(p) && self.b.is_inside(p)
     }
+
+    fn bounds(&self) -> Aabb {
+        self.a.bounds().union(&self.b.bounds())
+    }
 }
 
 impl IntersectShape {
@@ -348,6 +465,80 @@ impl IntersectShape {
     }
 }
 
+struct DifferenceShape {
+    a: Box<Shape + Sync>,
+    b: Box<Shape + Sync>,
+}
+
+// `DifferenceShape::intersect` needs more than each child's single nearest
+// hit: a ray can pass through several invalid crossings of a child (e.g.
+// entering `a` inside the notch carved by `b`) before reaching the first
+// point that actually lies on the difference's boundary. This walks a
+// child's hits outward, re-intersecting from just past each rejected point,
+// until one satisfies `valid` or the child shape runs out of hits.
+const MAX_DIFFERENCE_STEPS: u32 = 64;
+
+fn first_valid_hit<F: Fn((f64, f64)) -> bool>(shape: &Shape,
+                                               p: (f64, f64),
+                                               d: (f64, f64),
+                                               valid: F)
+                                               -> Option<Intersection> {
+    let mut origin = p;
+    for _ in 0..MAX_DIFFERENCE_STEPS {
+        match shape.intersect(origin, d) {
+            Some(i) => {
+                if valid(i.point) {
+                    return Some(i);
+                }
+                origin = (i.point.0 + d.0 * EPSILON, i.point.1 + d.1 * EPSILON);
+            }
+            None => return None,
+        }
+    }
+    None
+}
+
+impl Shape for DifferenceShape {
+    fn intersect(&self, p: (f64, f64), d: (f64, f64)) -> Option<Intersection> {
+        let hit_a = first_valid_hit(&*self.a, p, d, |pt| !self.b.is_inside(pt));
+        let hit_b = first_valid_hit(&*self.b, p, d, |pt| self.a.is_inside(pt)).map(|i| {
+            Intersection {
+                point: i.point,
+                normal: (-i.normal.0, -i.normal.1),
+            }
+        });
+        match (hit_a, hit_b) {
+            (Some(i1), Some(i2)) => {
+                if distance(p, i1.point) < distance(p, i2.point) {
+                    Some(i1)
+                } else {
+                    Some(i2)
+                }
+            }
+            (None, r2) => r2,
+            (r1, None) => r1,
+        }
+    }
+
+    fn is_inside(&self, p: (f64, f64)) -> bool {
+        self.a.is_inside(p) && !self.b.is_inside(p)
+    }
+
+    fn bounds(&self) -> Aabb {
+        // The difference is always a subset of `a`.
+        self.a.bounds()
+    }
+}
+
+impl DifferenceShape {
+    fn new(a: Box<Shape + Sync>, b: Box<Shape + Sync>) -> DifferenceShape {
+        DifferenceShape {
+            a: a,
+            b: b,
+        }
+    }
+}
+
 struct EntityIntersection {
     point: (f64, f64),
     normal: (f64, f64),
@@ -355,6 +546,7 @@ struct EntityIntersection {
     reflectivity: f64,
     eta: f64,
     absorption: Color,
+    diffuse: Color,
 }
 
 struct Entity {
@@ -363,6 +555,9 @@ struct Entity {
     reflectivity: f64,
     eta: f64,
     absorption: Color,
+    // Lambertian albedo; a zero (black) diffuse color means the surface
+    // doesn't scatter diffusely at all.
+    diffuse: Color,
 }
 
 impl Entity {
@@ -374,12 +569,26 @@ impl Entity {
             reflectivity: self.reflectivity,
             eta: self.eta,
             absorption: self.absorption,
+            diffuse: self.diffuse,
         })
     }
 }
 
 struct Scene {
     entities: Vec<Entity>,
+    bvh: bvh::Bvh,
+    // Entities with an unbounded shape (e.g. a `Plane`) can't live in the
+    // BVH, so they fall back to a linear scan.
+    unbounded: Vec<usize>,
+}
+
+struct RenderConfig {
+    width: u32,
+    height: u32,
+    samples: u32,
+    // Depth at which paths switch from unconditional bouncing to
+    // Russian-roulette termination (see `trace`).
+    max_depth: u32,
 }
 
 fn distance(p1: (f64, f64), p2: (f64, f64)) -> f64 {
@@ -389,19 +598,30 @@ fn distance(p1: (f64, f64), p2: (f64, f64)) -> f64 {
 }
 
 impl Scene {
+    fn new(entities: Vec<Entity>) -> Scene {
+        let bvh = bvh::Bvh::build(&entities);
+        let unbounded = entities.iter()
+            .enumerate()
+            .filter(|&(_, e)| !e.shape.bounds().is_finite())
+            .map(|(i, _)| i)
+            .collect();
+        Scene {
+            entities: entities,
+            bvh: bvh,
+            unbounded: unbounded,
+        }
+    }
+
     fn intersect(&self, p: (f64, f64), d: (f64, f64)) -> Option<EntityIntersection> {
-        let mut res: Option<EntityIntersection> = None;
-        for e in &self.entities {
-            if let Some(intersection) = e.intersect(p, d) {
-                res = match res {
-                    Some(r) => {
-                        if distance(p, r.point) > distance(p, intersection.point) {
-                            Some(intersection)
-                        } else {
-                            Some(r)
-                        }
-                    }
-                    None => Some(intersection),
+        let mut res = self.bvh.nearest(&self.entities, p, d);
+        for &i in &self.unbounded {
+            if let Some(intersection) = self.entities[i].intersect(p, d) {
+                let better = match res {
+                    Some(ref r) => distance(p, r.point) > distance(p, intersection.point),
+                    None => true,
+                };
+                if better {
+                    res = Some(intersection);
                 }
             }
         }
@@ -450,7 +670,50 @@ fn beer_lambert(a: Color, d: f64) -> Color {
     }
 }
 
-fn trace(scene: &Scene, ox: f64, oy: f64, dx: f64, dy: f64, depth: u32) -> Color {
+/// Draws the survive/terminate decision for Russian roulette. Below
+/// `min_depth` the path always continues (weight `1.0`); past it, it
+/// survives with probability `q = min(max(throughput.r, g, b), 0.95)` and the
+/// continued contribution must be divided by `q` to stay unbiased, so the
+/// caller gets `None` to stop or `Some(q)` to continue.
+fn roulette(rng: &mut ThreadRng, depth: u32, min_depth: u32, throughput: Color) -> Option<f64> {
+    if depth >= HARD_DEPTH_CAP {
+        None
+    } else if depth < min_depth {
+        Some(1.0)
+    } else {
+        // Cap q away from 1.0: a perfectly white surface (albedo or
+        // reflectivity of 1.0) would otherwise survive every roll and
+        // recurse forever.
+        let q = throughput.max_channel().min(0.95);
+        if q <= 0.0 || rng.gen_range(0.0, 1.0) >= q {
+            None
+        } else {
+            Some(q)
+        }
+    }
+}
+
+/// Samples a direction around the surface normal `(nx, ny)` from a
+/// cosine-weighted lobe: `phi = asin(2u - 1)` for `u` uniform in `[0, 1)`
+/// distributes the angular offset so that density follows `cos(phi)`, which
+/// cancels the BRDF's cosine term and the sampling pdf.
+fn cosine_scatter(rng: &mut ThreadRng, nx: f64, ny: f64) -> (f64, f64) {
+    let u: f64 = rng.gen_range(0.0, 1.0);
+    let phi = (2.0 * u - 1.0).asin();
+    let theta = ny.atan2(nx) + phi;
+    (theta.cos(), theta.sin())
+}
+
+fn trace(scene: &Scene,
+         rng: &mut ThreadRng,
+         ox: f64,
+         oy: f64,
+         dx: f64,
+         dy: f64,
+         depth: u32,
+         min_depth: u32,
+         throughput: Color)
+         -> Color {
     if let Some(r) = scene.intersect((ox, oy), (dx, dy)) {
         let sign = if r.normal.0 * dx + r.normal.1 * dy < 0.0 {
             1.0
@@ -458,36 +721,49 @@ fn trace(scene: &Scene, ox: f64, oy: f64, dx: f64, dy: f64, depth: u32) -> Color
             -1.0
         };
         let mut sum = r.emissive;
-        if depth < MAX_DEPTH && (r.reflectivity > 0.0 || r.eta > 0.0) {
-            let mut refl = r.reflectivity;
-            let (x, y) = r.point;
-            let nx = r.normal.0 * sign;
-            let ny = r.normal.1 * sign;
-            if r.eta > 0.0 {
-                let eta = if sign < 0.0 {
-                    r.eta
-                } else {
-                    1.0 / r.eta
-                };
-                match refract(dx, dy, nx, ny, eta) {
-                    Some((rx, ry)) => {
-                        let cosi = -(dx * nx + dy * ny);
-                        let cost = -(rx * nx + ry * ny);
-                        refl = if sign < 0.0 {
-                            schlick(cosi, cost, r.eta, 1.0)
-                        } else {
-                            schlick(cosi, cost, 1.0, r.eta)
-                        };
-                        sum = sum + trace(scene, x, y, rx, ry, depth + 1) * (1.0 - refl)
-                    }
-                    None => {
-                        refl = 1.0
+        let is_diffuse = r.diffuse.max_channel() > 0.0;
+        if r.reflectivity > 0.0 || r.eta > 0.0 || is_diffuse {
+            if let Some(q) = roulette(rng, depth, min_depth, throughput) {
+                let mut refl = r.reflectivity;
+                let (x, y) = r.point;
+                let nx = r.normal.0 * sign;
+                let ny = r.normal.1 * sign;
+                if r.eta > 0.0 {
+                    let eta = if sign < 0.0 {
+                        r.eta
+                    } else {
+                        1.0 / r.eta
+                    };
+                    match refract(dx, dy, nx, ny, eta) {
+                        Some((rx, ry)) => {
+                            let cosi = -(dx * nx + dy * ny);
+                            let cost = -(rx * nx + ry * ny);
+                            refl = if sign < 0.0 {
+                                schlick(cosi, cost, r.eta, 1.0)
+                            } else {
+                                schlick(cosi, cost, 1.0, r.eta)
+                            };
+                            let weight = (1.0 - refl) / q;
+                            sum = sum +
+                                  trace(scene, rng, x, y, rx, ry, depth + 1, min_depth, throughput * weight) * weight
+                        }
+                        None => {
+                            refl = 1.0
+                        }
                     }
                 }
-            }
-            if refl > 0.0 {
-                let (rx, ry) = reflect(dx, dy, nx, ny);
-                sum = sum + trace(scene, x, y, rx, ry, depth + 1) * refl;
+                if refl > 0.0 {
+                    let (rx, ry) = reflect(dx, dy, nx, ny);
+                    let weight = refl / q;
+                    sum = sum +
+                          trace(scene, rng, x, y, rx, ry, depth + 1, min_depth, throughput * weight) * weight;
+                }
+                if is_diffuse {
+                    let (rx, ry) = cosine_scatter(rng, nx, ny);
+                    let weight = r.diffuse * (1.0 / q);
+                    sum = sum +
+                          trace(scene, rng, x, y, rx, ry, depth + 1, min_depth, throughput * weight) * weight;
+                }
             }
         }
         if sign < 0.0 {
@@ -499,51 +775,31 @@ fn trace(scene: &Scene, ox: f64, oy: f64, dx: f64, dy: f64, depth: u32) -> Color
     }
 }
 
-fn sample(scene: &Scene, rng: &mut ThreadRng, x: f64, y: f64) -> Color {
-    let sum: Color = (0..N).map(|i| 2.0 * PI * (i as f64 + rng.gen_range(0.0, 1.0)) / N as f64)
+fn sample(scene: &Scene, rng: &mut ThreadRng, config: &RenderConfig, x: f64, y: f64) -> Color {
+    let samples = config.samples;
+    let min_depth = config.max_depth;
+    let sum: Color = (0..samples)
+        .map(|i| 2.0 * PI * (i as f64 + rng.gen_range(0.0, 1.0)) / samples as f64)
         .collect::<Vec<f64>>()
         .par_iter()
-        .map(|a| trace(scene, x, y, a.cos(), a.sin(), 0))
+        .map(|a| {
+            let mut local_rng = rand::thread_rng();
+            trace(scene, &mut local_rng, x, y, a.cos(), a.sin(), 0, min_depth, Color::white())
+        })
         .sum();
-    sum * (1.0 / N as f64)
+    sum * (1.0 / samples as f64)
 }
 
 fn main() {
-    let mut img = ImageBuffer::from_pixel(W, H, Rgb([0u8, 0u8, 0u8]));
+    let path = std::env::args().nth(1).expect("usage: light2d <scene.json>");
+    let (config, scene) = scene::load(&path);
+    let mut img = ImageBuffer::from_pixel(config.width, config.height, Rgb([0u8, 0u8, 0u8]));
     let mut rng = rand::thread_rng();
-    let scene = Scene {
-        entities: vec![Entity {
-            shape: Box::new(Circle {
-                cx: -0.2,
-                cy: -0.2,
-                r: 0.1,
-            }),
-            emissive: Color {
-                r: 10.0,
-                g: 10.0,
-                b: 10.0,
-            },
-            reflectivity: 0.0,
-            eta: 0.0,
-            absorption: Color::black(),
-        },
-        Entity {
-            shape: Box::new(Polygon::rectangle(0.5, 0.5, 0.0, 0.3, 0.2)),
-            emissive: Color::black(),
-            reflectivity: 0.2,
-            eta: 1.5,
-            absorption: Color {
-                r: 4.0,
-                g: 4.0,
-                b: 4.0,
-            },
-        }],
-    };
-    for x in 0..W {
-        for y in 0..H {
-            let xx = x as f64 / W as f64;
-            let yy = y as f64 / H as f64;
-            let color = sample(&scene, &mut rng, xx, yy);
+    for x in 0..config.width {
+        for y in 0..config.height {
+            let xx = x as f64 / config.width as f64;
+            let yy = y as f64 / config.height as f64;
+            let color = sample(&scene, &mut rng, &config, xx, yy);
             let r = min((color.r * 255.0) as u32, 255) as u8;
             let g = min((color.g * 255.0) as u32, 255) as u8;
             let b = min((color.b * 255.0) as u32, 255) as u8;
@@ -0,0 +1,191 @@
+// JSON scene description loader.
+//
+// A scene file is a top-level object with `width`, `height`, `samples`,
+// `max_depth` and an `entities` array. Each entity carries a tagged `shape`
+// (`circle`, `plane`, `polygon`, `rectangle`, `union`, `intersect`,
+// `difference`, the CSG variants nesting arbitrarily deep, the SDF variants
+// `circle_sdf`, `plane_sdf`, `box`, `ring`, `smooth_union`, and `transform`,
+// which wraps a child shape in a chain of translate/rotate/scale ops) plus
+// the usual material fields. See `scene.json` at the repo root for a
+// worked example.
+
+use std::fs::File;
+use std::io::Read;
+
+use {Circle, Color, DifferenceShape, Entity, IntersectShape, Plane, Polygon, RenderConfig, Scene,
+     Shape, UnionShape};
+use sdf::{MarchedShape, Sdf, SdfBox, SdfCircle, SdfHalfPlane, SdfRing, SmoothUnion};
+use transform::Transform;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ShapeDesc {
+    Circle { cx: f64, cy: f64, r: f64 },
+    Plane { px: f64, py: f64, nx: f64, ny: f64 },
+    Polygon { points: Vec<(f64, f64)> },
+    Rectangle { cx: f64, cy: f64, theta: f64, sx: f64, sy: f64 },
+    Union { a: Box<ShapeDesc>, b: Box<ShapeDesc> },
+    Intersect { a: Box<ShapeDesc>, b: Box<ShapeDesc> },
+    Difference { a: Box<ShapeDesc>, b: Box<ShapeDesc> },
+    Transform { ops: Vec<TransformOp>, shape: Box<ShapeDesc> },
+    CircleSdf { cx: f64, cy: f64, r: f64 },
+    PlaneSdf { px: f64, py: f64, nx: f64, ny: f64 },
+    Box { cx: f64, cy: f64, hx: f64, hy: f64 },
+    Ring { cx: f64, cy: f64, r: f64, thickness: f64 },
+    SmoothUnion { a: Box<SdfDesc>, b: Box<SdfDesc>, k: f64 },
+}
+
+impl ShapeDesc {
+    fn build(&self) -> Box<Shape + Sync> {
+        match *self {
+            ShapeDesc::Circle { cx, cy, r } => Box::new(Circle { cx: cx, cy: cy, r: r }),
+            ShapeDesc::Plane { px, py, nx, ny } => {
+                Box::new(Plane { px: px, py: py, nx: nx, ny: ny })
+            }
+            ShapeDesc::Polygon { ref points } => Box::new(Polygon::new(points.clone())),
+            ShapeDesc::Rectangle { cx, cy, theta, sx, sy } => {
+                Box::new(Polygon::rectangle(cx, cy, theta, sx, sy))
+            }
+            ShapeDesc::Union { ref a, ref b } => Box::new(UnionShape::new(a.build(), b.build())),
+            ShapeDesc::Intersect { ref a, ref b } => {
+                Box::new(IntersectShape::new(a.build(), b.build()))
+            }
+            ShapeDesc::Difference { ref a, ref b } => {
+                Box::new(DifferenceShape::new(a.build(), b.build()))
+            }
+            ShapeDesc::Transform { ref ops, ref shape } => {
+                let transform = ops.iter()
+                    .fold(Transform::identity(), |acc, op| op.to_transform().compose(&acc));
+                Box::new(transform.wrap(shape.build()))
+            }
+            ShapeDesc::CircleSdf { cx, cy, r } => {
+                Box::new(MarchedShape { sdf: Box::new(SdfCircle { cx: cx, cy: cy, r: r }) })
+            }
+            ShapeDesc::PlaneSdf { px, py, nx, ny } => {
+                Box::new(MarchedShape {
+                    sdf: Box::new(SdfHalfPlane { px: px, py: py, nx: nx, ny: ny }),
+                })
+            }
+            ShapeDesc::Box { cx, cy, hx, hy } => {
+                Box::new(MarchedShape { sdf: Box::new(SdfBox { cx: cx, cy: cy, hx: hx, hy: hy }) })
+            }
+            ShapeDesc::Ring { cx, cy, r, thickness } => {
+                Box::new(MarchedShape {
+                    sdf: Box::new(SdfRing { cx: cx, cy: cy, r: r, thickness: thickness }),
+                })
+            }
+            ShapeDesc::SmoothUnion { ref a, ref b, k } => {
+                Box::new(MarchedShape {
+                    sdf: Box::new(SmoothUnion { a: a.build(), b: b.build(), k: k }),
+                })
+            }
+        }
+    }
+}
+
+/// The SDF-only counterpart of `ShapeDesc`, used inside `ShapeDesc::SmoothUnion`
+/// so that smooth blending only ever combines true SDF primitives (closed-form
+/// shapes have no `Sdf` impl to blend with).
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SdfDesc {
+    CircleSdf { cx: f64, cy: f64, r: f64 },
+    PlaneSdf { px: f64, py: f64, nx: f64, ny: f64 },
+    Box { cx: f64, cy: f64, hx: f64, hy: f64 },
+    Ring { cx: f64, cy: f64, r: f64, thickness: f64 },
+    SmoothUnion { a: Box<SdfDesc>, b: Box<SdfDesc>, k: f64 },
+}
+
+impl SdfDesc {
+    fn build(&self) -> Box<Sdf + Sync> {
+        match *self {
+            SdfDesc::CircleSdf { cx, cy, r } => Box::new(SdfCircle { cx: cx, cy: cy, r: r }),
+            SdfDesc::PlaneSdf { px, py, nx, ny } => {
+                Box::new(SdfHalfPlane { px: px, py: py, nx: nx, ny: ny })
+            }
+            SdfDesc::Box { cx, cy, hx, hy } => {
+                Box::new(SdfBox { cx: cx, cy: cy, hx: hx, hy: hy })
+            }
+            SdfDesc::Ring { cx, cy, r, thickness } => {
+                Box::new(SdfRing { cx: cx, cy: cy, r: r, thickness: thickness })
+            }
+            SdfDesc::SmoothUnion { ref a, ref b, k } => {
+                Box::new(SmoothUnion { a: a.build(), b: b.build(), k: k })
+            }
+        }
+    }
+}
+
+/// One step of a `ShapeDesc::Transform`'s op chain, applied in list order
+/// (`ops[0]` first).
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum TransformOp {
+    Translate { tx: f64, ty: f64 },
+    Rotate { theta: f64 },
+    Scale { sx: f64, sy: f64 },
+}
+
+impl TransformOp {
+    fn to_transform(&self) -> Transform {
+        match *self {
+            TransformOp::Translate { tx, ty } => Transform::translate(tx, ty),
+            TransformOp::Rotate { theta } => Transform::rotate(theta),
+            TransformOp::Scale { sx, sy } => Transform::scale(sx, sy),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EntityDesc {
+    shape: ShapeDesc,
+    #[serde(default)]
+    emissive: Color,
+    #[serde(default)]
+    reflectivity: f64,
+    #[serde(default)]
+    eta: f64,
+    #[serde(default)]
+    absorption: Color,
+    #[serde(default)]
+    diffuse: Color,
+}
+
+impl EntityDesc {
+    fn build(self) -> Entity {
+        Entity {
+            shape: self.shape.build(),
+            emissive: self.emissive,
+            reflectivity: self.reflectivity,
+            eta: self.eta,
+            absorption: self.absorption,
+            diffuse: self.diffuse,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SceneDesc {
+    width: u32,
+    height: u32,
+    samples: u32,
+    // Depth at which paths switch to Russian-roulette termination.
+    max_depth: u32,
+    entities: Vec<EntityDesc>,
+}
+
+pub fn load(path: &str) -> (RenderConfig, Scene) {
+    let mut data = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut data))
+        .expect("failed to read scene file");
+    let desc: SceneDesc = serde_json::from_str(&data).expect("failed to parse scene file");
+    let config = RenderConfig {
+        width: desc.width,
+        height: desc.height,
+        samples: desc.samples,
+        max_depth: desc.max_depth,
+    };
+    let entities = desc.entities.into_iter().map(EntityDesc::build).collect();
+    (config, Scene::new(entities))
+}
@@ -0,0 +1,151 @@
+// Signed-distance-field shapes, rendered by sphere tracing instead of
+// closed-form ray intersection. This lets shapes blend smoothly, which the
+// boolean `UnionShape`/`IntersectShape` CSG cannot do.
+
+use {Aabb, Intersection, Shape};
+
+const MAX_MARCH_DISTANCE: f64 = 10.0;
+const MAX_MARCH_STEPS: u32 = 256;
+const NORMAL_EPSILON: f64 = 1e-4;
+// The global `EPSILON` is sized for closed-form `t > EPSILON` guards, not
+// sphere-tracing convergence: at grazing angles, shrinking the hit test down
+// to 1e-6 can take more steps than `MAX_MARCH_STEPS` allows, eroding edges
+// with spurious misses. Use the same coarser scale as `NORMAL_EPSILON`.
+const MARCH_EPSILON: f64 = 1e-4;
+
+pub trait Sdf {
+    fn dist(&self, p: (f64, f64)) -> f64;
+}
+
+pub struct SdfCircle {
+    pub cx: f64,
+    pub cy: f64,
+    pub r: f64,
+}
+
+impl Sdf for SdfCircle {
+    fn dist(&self, p: (f64, f64)) -> f64 {
+        let dx = p.0 - self.cx;
+        let dy = p.1 - self.cy;
+        (dx * dx + dy * dy).sqrt() - self.r
+    }
+}
+
+pub struct SdfHalfPlane {
+    pub px: f64,
+    pub py: f64,
+    pub nx: f64,
+    pub ny: f64,
+}
+
+impl Sdf for SdfHalfPlane {
+    fn dist(&self, p: (f64, f64)) -> f64 {
+        (p.0 - self.px) * self.nx + (p.1 - self.py) * self.ny
+    }
+}
+
+pub struct SdfBox {
+    pub cx: f64,
+    pub cy: f64,
+    pub hx: f64,
+    pub hy: f64,
+}
+
+impl Sdf for SdfBox {
+    fn dist(&self, p: (f64, f64)) -> f64 {
+        let qx = (p.0 - self.cx).abs() - self.hx;
+        let qy = (p.1 - self.cy).abs() - self.hy;
+        let ox = qx.max(0.0);
+        let oy = qy.max(0.0);
+        (ox * ox + oy * oy).sqrt() + qx.max(qy).min(0.0)
+    }
+}
+
+pub struct SdfRing {
+    pub cx: f64,
+    pub cy: f64,
+    pub r: f64,
+    pub thickness: f64,
+}
+
+impl Sdf for SdfRing {
+    fn dist(&self, p: (f64, f64)) -> f64 {
+        let dx = p.0 - self.cx;
+        let dy = p.1 - self.cy;
+        ((dx * dx + dy * dy).sqrt() - self.r).abs() - self.thickness
+    }
+}
+
+pub struct SmoothUnion {
+    pub a: Box<Sdf + Sync>,
+    pub b: Box<Sdf + Sync>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothUnion {
+    fn dist(&self, p: (f64, f64)) -> f64 {
+        smin(self.a.dist(p), self.b.dist(p), self.k)
+    }
+}
+
+/// Exponential smooth-min: blends `a` and `b` with a rounding radius that
+/// shrinks as `k` grows (large `k` approaches a hard `min`).
+pub fn smin(a: f64, b: f64, k: f64) -> f64 {
+    -((-k * a).exp() + (-k * b).exp()).ln() / k
+}
+
+fn normal(sdf: &Sdf, p: (f64, f64)) -> (f64, f64) {
+    let ex = (NORMAL_EPSILON, 0.0);
+    let ey = (0.0, NORMAL_EPSILON);
+    let nx = sdf.dist((p.0 + ex.0, p.1 + ex.1)) - sdf.dist((p.0 - ex.0, p.1 - ex.1));
+    let ny = sdf.dist((p.0 + ey.0, p.1 + ey.1)) - sdf.dist((p.0 - ey.0, p.1 - ey.1));
+    let len = (nx * nx + ny * ny).sqrt();
+    (nx / len, ny / len)
+}
+
+/// Sphere-march along the ray from `p` in direction `d` until the SDF reports
+/// a hit (`|dist| < MARCH_EPSILON`, past the ray origin) or the ray has gone
+/// further than `MAX_MARCH_DISTANCE` (a miss). Stepping by `dist.abs()`
+/// rather than `dist` lets this march correctly from inside a shape too
+/// (e.g. a dielectric SDF's exit ray), where the signed distance starts
+/// negative.
+pub fn march(sdf: &Sdf, p: (f64, f64), d: (f64, f64)) -> Option<Intersection> {
+    let mut t = 0.0;
+    for _ in 0..MAX_MARCH_STEPS {
+        let cur = (p.0 + d.0 * t, p.1 + d.1 * t);
+        let dist = sdf.dist(cur);
+        if dist.abs() < MARCH_EPSILON && t > MARCH_EPSILON {
+            return Some(Intersection {
+                point: cur,
+                normal: normal(sdf, cur),
+            });
+        }
+        t += dist.abs().max(MARCH_EPSILON);
+        if t > MAX_MARCH_DISTANCE {
+            return None;
+        }
+    }
+    None
+}
+
+/// Adapts any `Sdf` to the `Shape` trait so it can be traced like the
+/// closed-form shapes.
+pub struct MarchedShape {
+    pub sdf: Box<Sdf + Sync>,
+}
+
+impl Shape for MarchedShape {
+    fn intersect(&self, p: (f64, f64), d: (f64, f64)) -> Option<Intersection> {
+        march(&*self.sdf, p, d)
+    }
+
+    fn is_inside(&self, p: (f64, f64)) -> bool {
+        self.sdf.dist(p) < 0.0
+    }
+
+    fn bounds(&self) -> Aabb {
+        // A general SDF can describe an unbounded shape, so marched shapes
+        // are always left out of the BVH and checked by the scene's linear scan.
+        Aabb::infinite()
+    }
+}
@@ -0,0 +1,146 @@
+// Wraps a shape with a 2D affine transform so it can be translated,
+// rotated and scaled, something the CSG shapes and `Circle` cannot do on
+// their own (only `Polygon::rectangle` bakes rotation into its vertices).
+// `Transform` builds up the matrix; `Transform::wrap` attaches it to a
+// shape, producing a `TransformShape`.
+
+use {Aabb, Intersection, Shape};
+
+/// A 2x3 affine matrix `[[a, b, tx], [c, d, ty]]` mapping `(x, y)` to
+/// `(a*x + b*y + tx, c*x + d*y + ty)`.
+#[derive(Clone, Copy)]
+struct Matrix {
+    a: f64,
+    b: f64,
+    tx: f64,
+    c: f64,
+    d: f64,
+    ty: f64,
+}
+
+impl Matrix {
+    fn identity() -> Matrix {
+        Matrix { a: 1.0, b: 0.0, tx: 0.0, c: 0.0, d: 1.0, ty: 0.0 }
+    }
+
+    fn apply(&self, p: (f64, f64)) -> (f64, f64) {
+        (self.a * p.0 + self.b * p.1 + self.tx, self.c * p.0 + self.d * p.1 + self.ty)
+    }
+
+    /// Applies the linear part only, ignoring translation; used for
+    /// direction vectors.
+    fn apply_linear(&self, v: (f64, f64)) -> (f64, f64) {
+        (self.a * v.0 + self.b * v.1, self.c * v.0 + self.d * v.1)
+    }
+
+    fn inverse(&self) -> Matrix {
+        let det = self.a * self.d - self.b * self.c;
+        let a = self.d / det;
+        let b = -self.b / det;
+        let c = -self.c / det;
+        let d = self.a / det;
+        let tx = -(a * self.tx + b * self.ty);
+        let ty = -(c * self.tx + d * self.ty);
+        Matrix { a: a, b: b, tx: tx, c: c, d: d, ty: ty }
+    }
+
+    fn compose(&self, other: &Matrix) -> Matrix {
+        Matrix {
+            a: self.a * other.a + self.b * other.c,
+            b: self.a * other.b + self.b * other.d,
+            tx: self.a * other.tx + self.b * other.ty + self.tx,
+            c: self.c * other.a + self.d * other.c,
+            d: self.c * other.b + self.d * other.d,
+            ty: self.c * other.tx + self.d * other.ty + self.ty,
+        }
+    }
+}
+
+/// A composable 2D affine transform, built up from `translate`/`rotate`/
+/// `scale`/`compose` and finally attached to a shape with `wrap`. Keeping
+/// the matrix separate from any shape lets callers fold an arbitrary chain
+/// of ops together before wrapping, rather than threading a dummy shape
+/// through each intermediate step.
+#[derive(Clone, Copy)]
+pub struct Transform {
+    matrix: Matrix,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform { matrix: Matrix::identity() }
+    }
+
+    pub fn translate(tx: f64, ty: f64) -> Transform {
+        Transform { matrix: Matrix { tx: tx, ty: ty, ..Matrix::identity() } }
+    }
+
+    pub fn rotate(theta: f64) -> Transform {
+        let (sin, cos) = theta.sin_cos();
+        Transform { matrix: Matrix { a: cos, b: -sin, c: sin, d: cos, ..Matrix::identity() } }
+    }
+
+    pub fn scale(sx: f64, sy: f64) -> Transform {
+        Transform { matrix: Matrix { a: sx, d: sy, ..Matrix::identity() } }
+    }
+
+    /// Folds `self` on top of `inner`, i.e. `inner` is applied first, then
+    /// `self`.
+    pub fn compose(&self, inner: &Transform) -> Transform {
+        Transform { matrix: self.matrix.compose(&inner.matrix) }
+    }
+
+    pub fn wrap(&self, shape: Box<Shape + Sync>) -> TransformShape {
+        TransformShape::from_matrix(shape, self.matrix)
+    }
+}
+
+pub struct TransformShape {
+    shape: Box<Shape + Sync>,
+    forward: Matrix,
+    inverse: Matrix,
+}
+
+impl TransformShape {
+    fn from_matrix(shape: Box<Shape + Sync>, forward: Matrix) -> TransformShape {
+        let inverse = forward.inverse();
+        TransformShape { shape: shape, forward: forward, inverse: inverse }
+    }
+}
+
+impl Shape for TransformShape {
+    fn intersect(&self, p: (f64, f64), d: (f64, f64)) -> Option<Intersection> {
+        let local_p = self.inverse.apply(p);
+        let local_d = self.inverse.apply_linear(d);
+        self.shape.intersect(local_p, local_d).map(|i| {
+            let nx = self.inverse.a * i.normal.0 + self.inverse.c * i.normal.1;
+            let ny = self.inverse.b * i.normal.0 + self.inverse.d * i.normal.1;
+            let len = (nx * nx + ny * ny).sqrt();
+            Intersection {
+                point: self.forward.apply(i.point),
+                normal: (nx / len, ny / len),
+            }
+        })
+    }
+
+    fn is_inside(&self, p: (f64, f64)) -> bool {
+        self.shape.is_inside(self.inverse.apply(p))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let b = self.shape.bounds();
+        if !b.is_finite() {
+            return b;
+        }
+        let corners = [(b.min.0, b.min.1), (b.max.0, b.min.1), (b.min.0, b.max.1),
+                        (b.max.0, b.max.1)];
+        let mapped: Vec<(f64, f64)> = corners.iter().map(|&p| self.forward.apply(p)).collect();
+        let first = mapped[0];
+        mapped[1..].iter().fold(Aabb { min: first, max: first }, |acc, &(x, y)| {
+            Aabb {
+                min: (acc.min.0.min(x), acc.min.1.min(y)),
+                max: (acc.max.0.max(x), acc.max.1.max(y)),
+            }
+        })
+    }
+}